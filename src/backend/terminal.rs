@@ -4,12 +4,15 @@
 
 #![cfg(feature = "terminal")]
 
-use std::{cell::{Cell}, io::{self, BufWriter, Stdout, Write}, time::Duration};
+use std::{cell::{Cell, RefCell}, io::{self, BufWriter, Stdout, Write}, time::Duration};
 
+use enumset::EnumSet;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 use crate::{
     backend,
-    event::{Event, Key, MouseButton, MouseEvent},
+    event::{Event, Key, MouseButton, MouseEvent, MouseModifiers},
     theme,
     vec::Vec2,
 };
@@ -129,6 +132,30 @@ impl From<TKeyEvent> for Event {
     }
 }
 
+impl From<TKeyModifiers> for MouseModifiers {
+    fn from(modifiers: TKeyModifiers) -> Self {
+        const CTRL_ALT: TKeyModifiers = TKeyModifiers::from_bits_truncate(
+            TKeyModifiers::CONTROL.bits() | TKeyModifiers::ALT.bits(),
+        );
+        const CTRL_SHIFT: TKeyModifiers = TKeyModifiers::from_bits_truncate(
+            TKeyModifiers::CONTROL.bits() | TKeyModifiers::SHIFT.bits(),
+        );
+        const ALT_SHIFT: TKeyModifiers = TKeyModifiers::from_bits_truncate(
+            TKeyModifiers::ALT.bits() | TKeyModifiers::SHIFT.bits(),
+        );
+
+        match modifiers {
+            CTRL_ALT => MouseModifiers::CtrlAlt,
+            CTRL_SHIFT => MouseModifiers::CtrlShift,
+            ALT_SHIFT => MouseModifiers::AltShift,
+            TKeyModifiers::CONTROL => MouseModifiers::Ctrl,
+            TKeyModifiers::ALT => MouseModifiers::Alt,
+            TKeyModifiers::SHIFT => MouseModifiers::Shift,
+            _ => MouseModifiers::None,
+        }
+    }
+}
+
 impl From<theme::Color> for TColor {
     fn from(base_color: theme::Color) -> Self {
         match base_color {
@@ -169,11 +196,146 @@ impl From<theme::Color> for TColor {
     }
 }
 
+/// Turns an active `theme::Effect` into the attribute that enables it.
+fn on_attr(effect: theme::Effect) -> Option<TAttribute> {
+    match effect {
+        theme::Effect::Simple => None,
+        theme::Effect::Reverse => Some(TAttribute::Reversed),
+        theme::Effect::Bold => Some(TAttribute::Bold),
+        theme::Effect::Italic => Some(TAttribute::Italic),
+        theme::Effect::Underline => Some(TAttribute::Underlined),
+    }
+}
+
+/// Turns an inactive `theme::Effect` into the attribute that disables it.
+fn off_attr(effect: theme::Effect) -> Option<TAttribute> {
+    match effect {
+        theme::Effect::Simple => None,
+        theme::Effect::Reverse => Some(TAttribute::ReversedOff),
+        theme::Effect::Bold => Some(TAttribute::NormalIntensity),
+        theme::Effect::Italic => Some(TAttribute::ItalicOff),
+        theme::Effect::Underline => Some(TAttribute::UnderlinedOff),
+    }
+}
+
+/// Color capability of the terminal we're talking to, probed once at
+/// `init()` time from `COLORTERM`/`TERM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorSupport {
+    /// No color support; everything renders with the terminal's default.
+    NoColor,
+    /// The 16 basic ANSI colors.
+    Ansi16,
+    /// The 256-color palette.
+    Ansi256,
+    /// 24-bit RGB.
+    TrueColor,
+}
+
+impl ColorSupport {
+    /// Guesses what the terminal can render from its environment.
+    fn probe() -> Self {
+        if let Ok(colorterm) = std::env::var("COLORTERM") {
+            if colorterm.contains("truecolor") || colorterm.contains("24bit") {
+                return ColorSupport::TrueColor;
+            }
+        }
+
+        match std::env::var("TERM") {
+            Ok(term) if term.contains("256color") => ColorSupport::Ansi256,
+            Ok(term) if term == "dumb" => ColorSupport::NoColor,
+            Ok(_) => ColorSupport::Ansi16,
+            Err(_) => ColorSupport::NoColor,
+        }
+    }
+}
+
+/// Quantizes a 24-bit color down to an index of the 256-color palette's
+/// 6x6x6 color cube, scaling each component by 5/255 and rounding down.
+fn rgb_to_256_index(r: u8, g: u8, b: u8) -> u8 {
+    let scale = |c: u8| (c as u16 * 5 / 255) as u8;
+    16 + 36 * scale(r) + 6 * scale(g) + scale(b)
+}
+
+/// Finds the closest of the 16 base ANSI colors to an RGB triple, for
+/// terminals that can do neither 256-color nor truecolor output.
+fn nearest_base_color(r: u8, g: u8, b: u8) -> theme::Color {
+    const PALETTE: [(theme::Color, (i32, i32, i32)); 16] = [
+        (theme::Color::Dark(theme::BaseColor::Black), (0, 0, 0)),
+        (theme::Color::Dark(theme::BaseColor::Red), (128, 0, 0)),
+        (theme::Color::Dark(theme::BaseColor::Green), (0, 128, 0)),
+        (theme::Color::Dark(theme::BaseColor::Yellow), (128, 128, 0)),
+        (theme::Color::Dark(theme::BaseColor::Blue), (0, 0, 128)),
+        (theme::Color::Dark(theme::BaseColor::Magenta), (128, 0, 128)),
+        (theme::Color::Dark(theme::BaseColor::Cyan), (0, 128, 128)),
+        (theme::Color::Dark(theme::BaseColor::White), (192, 192, 192)),
+        (theme::Color::Light(theme::BaseColor::Black), (128, 128, 128)),
+        (theme::Color::Light(theme::BaseColor::Red), (255, 0, 0)),
+        (theme::Color::Light(theme::BaseColor::Green), (0, 255, 0)),
+        (theme::Color::Light(theme::BaseColor::Yellow), (255, 255, 0)),
+        (theme::Color::Light(theme::BaseColor::Blue), (0, 0, 255)),
+        (theme::Color::Light(theme::BaseColor::Magenta), (255, 0, 255)),
+        (theme::Color::Light(theme::BaseColor::Cyan), (0, 255, 255)),
+        (theme::Color::Light(theme::BaseColor::White), (255, 255, 255)),
+    ];
+
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    PALETTE
+        .iter()
+        .min_by_key(|(_, (pr, pg, pb))| {
+            let (dr, dg, db) = (pr - r, pg - g, pb - b);
+            dr * dr + dg * dg + db * db
+        })
+        .map(|(color, _)| *color)
+        .unwrap()
+}
+
+/// A single screen cell: the grapheme drawn there and the style it was drawn with.
+#[derive(Clone, PartialEq)]
+struct BufferCell {
+    text: String,
+    colors: theme::ColorPair,
+    effects: EnumSet<theme::Effect>,
+}
+
+impl Default for BufferCell {
+    fn default() -> Self {
+        BufferCell {
+            text: " ".to_string(),
+            colors: theme::ColorPair::from_256colors(0, 0),
+            effects: EnumSet::new(),
+        }
+    }
+}
+
+/// The style the terminal itself currently has applied, as opposed to the
+/// style a view last asked for. Used to avoid re-emitting color/attribute
+/// escapes when consecutive runs already share them.
+#[derive(Clone, Copy, PartialEq)]
+struct StyleState {
+    colors: theme::ColorPair,
+    effects: EnumSet<theme::Effect>,
+}
+
 /// Backend using terminal-backend
 pub struct Backend {
     current_style: Cell<theme::ColorPair>,
+    current_effects: Cell<EnumSet<theme::Effect>>,
     last_button: Option<MouseButton>,
-    terminal: Terminal<File>
+    terminal: Terminal<File>,
+    color_support: ColorSupport,
+    size: Cell<Vec2>,
+    // Double-buffered screen content: `back` is what views have drawn this
+    // frame, `front` is what the terminal currently shows. `refresh()` diffs
+    // the two and only sends the cells that changed.
+    front: RefCell<Vec<BufferCell>>,
+    back: RefCell<Vec<BufferCell>>,
+    // Set after a resize (or at startup) to force the next `refresh()` to
+    // repaint every cell instead of relying on the front/back diff.
+    force_full_repaint: Cell<bool>,
+    // What the terminal last had applied, or `None` right after a reset
+    // (e.g. a `clear()`) when the next emission should start from scratch.
+    emitted_style: Cell<Option<StyleState>>,
 }
 
 
@@ -188,20 +350,67 @@ impl Backend {
         terminal.act(Action::EnableRawMode).unwrap();
         terminal.act(Action::HideCursor).unwrap();
 
+        let size = if let Retrieved::TerminalSize(x, y) =
+            terminal.get(Value::TerminalSize).unwrap().into()
+        {
+            Vec2::new(x as usize, y as usize)
+        } else {
+            panic!("Not possible.");
+        };
+        let len = size.x * size.y;
+
         Ok(Box::new(Backend {
             current_style: Cell::new(theme::ColorPair::from_256colors(0, 0)),
+            current_effects: Cell::new(EnumSet::new()),
             last_button: None,
-            terminal
+            terminal,
+            color_support: ColorSupport::probe(),
+            size: Cell::new(size),
+            front: RefCell::new(vec![BufferCell::default(); len]),
+            back: RefCell::new(vec![BufferCell::default(); len]),
+            force_full_repaint: Cell::new(true),
+            emitted_style: Cell::new(None),
         }))
     }
 
-    fn apply_colors(&self, colors: theme::ColorPair) {
-        self.terminal.act(Action::SetForegroundColor(TColor::from(colors.front))).unwrap();
-        self.terminal.act(Action::SetBackgroundColor(TColor::from(colors.back))).unwrap();
+    /// Reallocates the front/back buffers if `size` changed, forcing a full
+    /// repaint on the next `refresh()`.
+    fn ensure_size(&self, size: Vec2) {
+        if self.size.get() != size {
+            let len = size.x * size.y;
+            *self.front.borrow_mut() = vec![BufferCell::default(); len];
+            *self.back.borrow_mut() = vec![BufferCell::default(); len];
+            self.size.set(size);
+            self.force_full_repaint.set(true);
+        }
     }
 
-    fn set_attr(&self, attr: TAttribute) {
-        self.terminal.act(Action::SetAttribute(attr)).unwrap();
+    /// Converts a theme color to the terminal color to actually emit, taking
+    /// this terminal's detected color capability into account so truecolor
+    /// sequences never get sent to a terminal that can't render them.
+    fn convert_color(&self, color: theme::Color) -> TColor {
+        match self.color_support {
+            ColorSupport::NoColor => TColor::Reset,
+            ColorSupport::TrueColor => TColor::from(color),
+            ColorSupport::Ansi256 => match color {
+                theme::Color::Rgb(r, g, b) => {
+                    TColor::AnsiValue(rgb_to_256_index(r, g, b))
+                }
+                other => TColor::from(other),
+            },
+            ColorSupport::Ansi16 => match color {
+                theme::Color::Rgb(r, g, b) => {
+                    TColor::from(nearest_base_color(r, g, b))
+                }
+                theme::Color::RgbLowRes(r, g, b) => {
+                    // Expand the 0-5 cube components back to 0-255 before
+                    // finding the nearest base color.
+                    let expand = |c: u8| c * 51;
+                    TColor::from(nearest_base_color(expand(r), expand(g), expand(b)))
+                }
+                other => TColor::from(other),
+            },
+        }
     }
 
     fn map_key(&mut self, event: TEvent) -> Event {
@@ -210,39 +419,56 @@ impl Backend {
             TEvent::Mouse(mouse_event) => {
                 let position;
                 let event;
+                let modifiers;
 
                 match mouse_event {
-                    TMouseEvent::Down(button, x, y, _) => {
+                    TMouseEvent::Down(button, x, y, m) => {
                         let button = MouseButton::from(button);
                         self.last_button = Some(button);
                         event = MouseEvent::Press(button);
                         position = (x, y).into();
+                        modifiers = MouseModifiers::from(m);
                     }
-                    TMouseEvent::Up(_, x, y, _) => {
+                    TMouseEvent::Up(_, x, y, m) => {
                         event = MouseEvent::Release(self.last_button.unwrap());
                         position = (x, y).into();
+                        modifiers = MouseModifiers::from(m);
                     }
-                    TMouseEvent::Drag(_, x, y, _) => {
+                    TMouseEvent::Drag(_, x, y, m) => {
                         event = MouseEvent::Hold(self.last_button.unwrap());
                         position = (x, y).into();
+                        modifiers = MouseModifiers::from(m);
                     }
-                    TMouseEvent::ScrollDown(x, y, _) => {
+                    TMouseEvent::ScrollDown(x, y, m) => {
                         event = MouseEvent::WheelDown;
                         position = (x, y).into();
+                        modifiers = MouseModifiers::from(m);
                     }
-                    TMouseEvent::ScrollUp(x, y, _) => {
-                        event = MouseEvent::WheelDown;
+                    TMouseEvent::ScrollUp(x, y, m) => {
+                        event = MouseEvent::WheelUp;
                         position = (x, y).into();
+                        modifiers = MouseModifiers::from(m);
                     }
+                    // `terminal`'s `TMouseEvent` (backed by crossterm) has no
+                    // horizontal-scroll variants yet, so `MouseEvent::WheelLeft`/
+                    // `WheelRight` can't be produced from here until it grows
+                    // them; the variants already exist on `MouseEvent` for
+                    // views to match against once a backend can send them.
                 };
 
                 Event::Mouse {
                     event,
                     position,
                     offset: Vec2::zero(),
+                    modifiers,
                 }
             }
-            TEvent::Resize => Event::WindowResize,
+            TEvent::Resize => {
+                // Reallocate up front so the size views see via `screen_size()`
+                // for this frame's relayout matches what `print_at` writes into.
+                self.ensure_size(self.screen_size());
+                Event::WindowResize
+            }
             TEvent::Unknown => Event::Unknown(vec![])
         }
     }
@@ -264,12 +490,101 @@ impl backend::Backend for Backend {
     }
 
     fn refresh(&mut self) {
-        self.terminal.flush_batch().unwrap();
+        let size = self.screen_size();
+        self.ensure_size(size);
+
+        let width = size.x;
+        let height = size.y;
+        let full_repaint = self.force_full_repaint.get();
+
+        if full_repaint {
+            self.terminal.act(Action::ClearTerminal(Clear::All)).unwrap();
+            self.emitted_style.set(None);
+        }
+
+        {
+            let front = self.front.borrow();
+            let back = self.back.borrow();
+            let mut lock = self.terminal.lock_mut().unwrap();
+
+            for y in 0..height {
+                let mut x = 0;
+                while x < width {
+                    let idx = y * width + x;
+                    if !full_repaint && front[idx] == back[idx] {
+                        x += 1;
+                        continue;
+                    }
+
+                    let run_start = x;
+                    let style = (back[idx].colors, back[idx].effects);
+                    let mut text = String::new();
+                    while x < width {
+                        let idx = y * width + x;
+                        let dirty = full_repaint || front[idx] != back[idx];
+                        if !dirty || (back[idx].colors, back[idx].effects) != style {
+                            break;
+                        }
+                        text.push_str(&back[idx].text);
+                        x += 1;
+                    }
+
+                    lock.act(Action::MoveCursorTo(run_start as u16, y as u16)).unwrap();
+
+                    let wanted = StyleState {
+                        colors: style.0,
+                        effects: style.1,
+                    };
+                    match self.emitted_style.get() {
+                        Some(emitted) if emitted == wanted => (),
+                        Some(emitted) => {
+                            if emitted.colors != wanted.colors {
+                                lock.act(Action::SetForegroundColor(self.convert_color(wanted.colors.front))).unwrap();
+                                lock.act(Action::SetBackgroundColor(self.convert_color(wanted.colors.back))).unwrap();
+                            }
+                            // Only flip the effect bits that actually changed.
+                            for effect in emitted.effects.symmetrical_difference(wanted.effects) {
+                                let attr = if wanted.effects.contains(effect) {
+                                    on_attr(effect)
+                                } else {
+                                    off_attr(effect)
+                                };
+                                if let Some(attr) = attr {
+                                    lock.act(Action::SetAttribute(attr)).unwrap();
+                                }
+                            }
+                        }
+                        None => {
+                            lock.act(Action::ResetColor).unwrap();
+                            lock.act(Action::SetForegroundColor(self.convert_color(wanted.colors.front))).unwrap();
+                            lock.act(Action::SetBackgroundColor(self.convert_color(wanted.colors.back))).unwrap();
+                            for effect in EnumSet::<theme::Effect>::all() {
+                                let attr = if wanted.effects.contains(effect) {
+                                    on_attr(effect)
+                                } else {
+                                    off_attr(effect)
+                                };
+                                if let Some(attr) = attr {
+                                    lock.act(Action::SetAttribute(attr)).unwrap();
+                                }
+                            }
+                        }
+                    }
+                    self.emitted_style.set(Some(wanted));
+
+                    lock.write_all(text.as_bytes()).unwrap();
+                }
+            }
+
+            lock.flush_batch().unwrap();
+        }
+
+        self.front.swap(&self.back);
+        self.force_full_repaint.set(false);
     }
 
     fn has_colors(&self) -> bool {
-        // TODO: color support detection?
-        true
+        self.color_support != ColorSupport::NoColor
     }
 
     fn screen_size(&self) -> Vec2 {
@@ -281,67 +596,99 @@ impl backend::Backend for Backend {
     }
 
     fn print_at(&self, pos: Vec2, text: &str) {
-        let mut lock = self.terminal.lock_mut().unwrap();
-        lock.act(Action::MoveCursorTo(pos.x as u16, pos.y as u16)).unwrap();
-        lock.write(text.as_bytes()).unwrap();
-        lock.flush_batch().unwrap();
+        // Resizing is handled once in `refresh()` (and on `WindowResize`), not
+        // here: probing `screen_size()` on every `print_at` call would turn a
+        // full-frame redraw back into thousands of terminal round-trips, and
+        // reallocating mid-frame would drop whatever was already drawn into
+        // `back` this frame.
+        let width = self.size.get().x;
+        let colors = self.current_style.get();
+        let effects = self.current_effects.get();
+        let mut back = self.back.borrow_mut();
+
+        let mut x = pos.x;
+        for grapheme in text.graphemes(true) {
+            if x >= width {
+                break;
+            }
+
+            // Wide glyphs (CJK, emoji, ...) occupy more than one column; give
+            // them a blank continuation cell so the buffer's column math
+            // stays in sync with cursive's unicode-width-based layout.
+            let cell_width = grapheme.width().max(1);
+
+            let idx = pos.y * width + x;
+            if let Some(cell) = back.get_mut(idx) {
+                cell.text = grapheme.to_string();
+                cell.colors = colors;
+                cell.effects = effects;
+            }
+            for continuation in 1..cell_width {
+                if x + continuation >= width {
+                    break;
+                }
+                let idx = pos.y * width + x + continuation;
+                if let Some(cell) = back.get_mut(idx) {
+                    cell.text = String::new();
+                    cell.colors = colors;
+                    cell.effects = effects;
+                }
+            }
+
+            x += cell_width;
+        }
     }
 
     fn print_at_rep(&self, pos: Vec2, repetitions: usize, text: &str) {
-        if repetitions > 0 {
-            let mut lock = self.terminal.lock_mut().unwrap();
-            lock.batch(Action::MoveCursorTo(pos.x as u16, pos.y as u16)).unwrap();
-            lock.write_all(text.as_bytes()).unwrap();
+        if repetitions == 0 {
+            return;
+        }
 
-            let mut dupes_left = repetitions - 1;
-            while dupes_left > 0 {
-                lock.write_all(text.as_bytes()).unwrap();
-                dupes_left -= 1;
-            }
+        let len = text.width();
+        for rep in 0..repetitions {
+            self.print_at(Vec2::new(pos.x + rep * len, pos.y), text);
         }
     }
 
     fn clear(&self, color: theme::Color) {
-        self.apply_colors(theme::ColorPair {
+        let colors = theme::ColorPair {
             front: color,
             back: color,
-        });
+        };
+
+        for cell in self.back.borrow_mut().iter_mut() {
+            cell.text = " ".to_string();
+            cell.colors = colors;
+            cell.effects = EnumSet::new();
+        }
 
-        self.terminal.act(Action::ClearTerminal(Clear::All)).unwrap();
+        // Make the next `refresh()` start its first run with a `ResetColor`
+        // and a full reapply of the wanted style, so no effect applied before
+        // the clear bleeds into the first cell redrawn.
+        self.emitted_style.set(None);
     }
 
     fn set_color(&self, color: theme::ColorPair) -> theme::ColorPair {
-        let current_style = self.current_style.get();
-
-        if current_style != color {
-            self.apply_colors(color);
-            self.current_style.set(color);
-        }
-
-        current_style
+        self.current_style.replace(color)
     }
 
     fn set_effect(&self, effect: theme::Effect) {
-        match effect {
-            theme::Effect::Simple => (),
-            theme::Effect::Reverse => self.set_attr(TAttribute::Reversed),
-            theme::Effect::Bold => self.set_attr(TAttribute::Bold),
-            theme::Effect::Italic => self.set_attr(TAttribute::Italic),
-            theme::Effect::Underline => self.set_attr(TAttribute::Underlined),
+        if effect != theme::Effect::Simple {
+            let mut effects = self.current_effects.get();
+            effects.insert(effect);
+            self.current_effects.set(effects);
         }
     }
 
     fn unset_effect(&self, effect: theme::Effect) {
-        match effect {
-            theme::Effect::Simple => (),
-            theme::Effect::Reverse => self.set_attr(TAttribute::ReversedOff),
-            theme::Effect::Bold => self.set_attr(TAttribute::NormalIntensity),
-            theme::Effect::Italic => self.set_attr(TAttribute::ItalicOff),
-            theme::Effect::Underline => self.set_attr(TAttribute::UnderlinedOff),
+        if effect != theme::Effect::Simple {
+            let mut effects = self.current_effects.get();
+            effects.remove(effect);
+            self.current_effects.set(effects);
         }
     }
 
     fn name(&self) -> &str {
         "terminal"
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,92 @@
+//! User-input events and their variants.
+
+use crate::vec::Vec2;
+
+/// Represents a key, independent of any modifier.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum Key {
+    Esc,
+    Backspace,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Del,
+    Ins,
+    Enter,
+    Tab,
+    F(u8),
+}
+
+impl Key {
+    /// Builds a `Key::F` from a function-key index, clamped to F1-F12.
+    pub fn from_f(n: u8) -> Key {
+        Key::F(n.max(1).min(12))
+    }
+}
+
+/// One of the mouse buttons.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Other,
+}
+
+/// Keyboard modifiers held down during a mouse event, mirroring the
+/// Ctrl/Alt/Shift combinations already recognized for keyboard events so
+/// views can tell a plain click from e.g. a Ctrl+Click or a Shift+drag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MouseModifiers {
+    None,
+    Ctrl,
+    Alt,
+    Shift,
+    CtrlAlt,
+    CtrlShift,
+    AltShift,
+}
+
+/// A mouse event, relative to the view it's sent to.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Hash)]
+pub enum MouseEvent {
+    Press(MouseButton),
+    Release(MouseButton),
+    Hold(MouseButton),
+    WheelUp,
+    WheelDown,
+    /// A horizontal (tilt) wheel was rolled left.
+    WheelLeft,
+    /// A horizontal (tilt) wheel was rolled right.
+    WheelRight,
+}
+
+/// Represents an event as seen by the application.
+#[derive(PartialEq, Eq, Clone, Debug, Hash)]
+pub enum Event {
+    Char(char),
+    CtrlChar(char),
+    AltChar(char),
+    Key(Key),
+    Shift(Key),
+    Alt(Key),
+    AltShift(Key),
+    Ctrl(Key),
+    CtrlShift(Key),
+    CtrlAlt(Key),
+    Mouse {
+        offset: Vec2,
+        position: Vec2,
+        event: MouseEvent,
+        /// Keyboard modifiers held down during the event.
+        modifiers: MouseModifiers,
+    },
+    WindowResize,
+    Exit,
+    Unknown(Vec<u8>),
+}